@@ -0,0 +1,10 @@
+//! Block device trait implemented by the concrete driver used by the kernel.
+use core::any::Any;
+
+/// A block device able to read and write fixed-size blocks identified by id.
+pub trait BlockDevice: Send + Sync + Any {
+    /// Read the block `block_id` into `buf`.
+    fn read_block(&self, block_id: usize, buf: &mut [u8]);
+    /// Write `buf` back to the block `block_id`.
+    fn write_block(&self, block_id: usize, buf: &[u8]);
+}
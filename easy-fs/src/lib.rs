@@ -0,0 +1,39 @@
+//! An easy file system isolated from the kernel.
+#![no_std]
+extern crate alloc;
+
+mod bitmap;
+mod block_cache;
+mod block_dev;
+mod efs;
+mod layout;
+mod vfs;
+
+/// Size in bytes of a single block.
+pub const BLOCK_SZ: usize = 512;
+
+use spin::Mutex;
+
+/// The kernel-supplied clock, read when stamping inode timestamps. It returns
+/// a monotonically increasing value (nanoseconds since boot); `None` before
+/// the kernel registers one, in which case timestamps are left at 0.
+static CLOCK: Mutex<Option<fn() -> u64>> = Mutex::new(None);
+
+/// Register the clock used to stamp inode timestamps.
+pub fn register_clock(clock: fn() -> u64) {
+    *CLOCK.lock() = Some(clock);
+}
+
+/// The current time per the registered clock, or 0 when none is registered.
+pub fn now() -> u64 {
+    (*CLOCK.lock()).map_or(0, |clock| clock())
+}
+
+use bitmap::Bitmap;
+use block_cache::{block_cache_sync_all, get_block_cache};
+use layout::{DiskInode, SuperBlock};
+
+pub use block_dev::BlockDevice;
+pub use efs::EasyFileSystem;
+pub use layout::{DirEntry, FileType, DIRENT_SZ};
+pub use vfs::Inode;
@@ -0,0 +1,292 @@
+//! The in-memory `Inode`, the handle the kernel works with.
+use super::{
+    block_cache_sync_all, get_block_cache, BlockDevice, DirEntry, DiskInode, EasyFileSystem,
+    FileType, DIRENT_SZ,
+};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::{Mutex, MutexGuard};
+
+/// A handle to one on-disk inode, located by its block and offset.
+pub struct Inode {
+    block_id: usize,
+    block_offset: usize,
+    inode_id: u32,
+    fs: Arc<Mutex<EasyFileSystem>>,
+    block_device: Arc<dyn BlockDevice>,
+}
+
+impl Inode {
+    /// Build a handle for the inode at (`block_id`, `block_offset`).
+    pub fn new(
+        block_id: u32,
+        block_offset: usize,
+        inode_id: u32,
+        fs: Arc<Mutex<EasyFileSystem>>,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Self {
+        Self {
+            block_id: block_id as usize,
+            block_offset,
+            inode_id,
+            fs,
+            block_device,
+        }
+    }
+    /// Read the backing `DiskInode` under `f`.
+    fn read_disk_inode<V>(&self, f: impl FnOnce(&DiskInode) -> V) -> V {
+        get_block_cache(self.block_id, Arc::clone(&self.block_device))
+            .lock()
+            .read(self.block_offset, f)
+    }
+    /// Mutate the backing `DiskInode` under `f`.
+    fn modify_disk_inode<V>(&self, f: impl FnOnce(&mut DiskInode) -> V) -> V {
+        get_block_cache(self.block_id, Arc::clone(&self.block_device))
+            .lock()
+            .modify(self.block_offset, f)
+    }
+    /// The inode number of this inode within its filesystem.
+    pub fn get_inode_id(&self) -> usize {
+        self.inode_id as usize
+    }
+    /// Current size of the file in bytes.
+    pub fn size(&self) -> usize {
+        self.read_disk_inode(|disk_inode| disk_inode.size as usize)
+    }
+    /// The kind of object this inode refers to.
+    pub fn file_type(&self) -> FileType {
+        self.read_disk_inode(|disk_inode| disk_inode.disk_inode_type())
+    }
+    /// Owning user id.
+    pub fn uid(&self) -> u32 {
+        self.read_disk_inode(|disk_inode| disk_inode.uid)
+    }
+    /// Owning group id.
+    pub fn gid(&self) -> u32 {
+        self.read_disk_inode(|disk_inode| disk_inode.gid)
+    }
+    /// Permission word (rwx triples plus setuid/setgid).
+    pub fn mode(&self) -> u16 {
+        self.read_disk_inode(|disk_inode| disk_inode.mode as u16)
+    }
+    /// Overwrite the permission word.
+    pub fn set_mode(&self, mode: u16) {
+        self.modify_disk_inode(|disk_inode| disk_inode.mode = mode as u32);
+        block_cache_sync_all();
+    }
+    /// Set the owning user and group ids, touching the status-change time.
+    pub fn set_owner(&self, uid: u32, gid: u32) {
+        let now = self.clock_now();
+        self.modify_disk_inode(|disk_inode| {
+            disk_inode.uid = uid;
+            disk_inode.gid = gid;
+            disk_inode.ctime = now;
+        });
+        block_cache_sync_all();
+    }
+    /// The current time, or 0 on a legacy image whose inodes lack timestamps.
+    fn clock_now(&self) -> u64 {
+        if self.fs.lock().is_legacy() {
+            0
+        } else {
+            super::now()
+        }
+    }
+    /// Last access time (0 on a legacy image).
+    pub fn atime(&self) -> u64 {
+        if self.fs.lock().is_legacy() {
+            return 0;
+        }
+        self.read_disk_inode(|disk_inode| disk_inode.atime)
+    }
+    /// Last modification time (0 on a legacy image).
+    pub fn mtime(&self) -> u64 {
+        if self.fs.lock().is_legacy() {
+            return 0;
+        }
+        self.read_disk_inode(|disk_inode| disk_inode.mtime)
+    }
+    /// Last status-change time (0 on a legacy image).
+    pub fn ctime(&self) -> u64 {
+        if self.fs.lock().is_legacy() {
+            return 0;
+        }
+        self.read_disk_inode(|disk_inode| disk_inode.ctime)
+    }
+    /// Stamp the status-change time, e.g. on a link-count change.
+    pub fn touch_ctime(&self) {
+        let now = self.clock_now();
+        self.modify_disk_inode(|disk_inode| disk_inode.ctime = now);
+        block_cache_sync_all();
+    }
+    /// Find the inode number bound to `name` in this directory.
+    fn find_inode_id(&self, name: &str, disk_inode: &DiskInode) -> Option<u32> {
+        assert!(disk_inode.is_dir());
+        let file_count = (disk_inode.size as usize) / DIRENT_SZ;
+        let mut dirent = DirEntry::empty();
+        for i in 0..file_count {
+            assert_eq!(
+                disk_inode.read_at(DIRENT_SZ * i, dirent.as_bytes_mut(), &self.block_device),
+                DIRENT_SZ,
+            );
+            if dirent.name() == name {
+                return Some(dirent.inode_number() as u32);
+            }
+        }
+        None
+    }
+    /// Build an `Inode` handle for inode number `inode_id`.
+    fn inode_at(&self, inode_id: u32, fs: &MutexGuard<EasyFileSystem>) -> Arc<Inode> {
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        Arc::new(Self::new(
+            block_id,
+            block_offset,
+            inode_id,
+            self.fs.clone(),
+            self.block_device.clone(),
+        ))
+    }
+    /// Look up `name` in this directory, if present.
+    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
+        let fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| {
+            self.find_inode_id(name, disk_inode)
+                .map(|inode_id| self.inode_at(inode_id, &fs))
+        })
+    }
+    /// Grow this inode to `new_size`, allocating data blocks as needed.
+    fn increase_size(
+        &self,
+        new_size: u32,
+        disk_inode: &mut DiskInode,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) {
+        if new_size < disk_inode.size {
+            return;
+        }
+        let blocks_needed = disk_inode.blocks_num_needed(new_size);
+        let mut v: Vec<u32> = Vec::new();
+        for _ in 0..blocks_needed {
+            v.push(fs.alloc_data());
+        }
+        disk_inode.increase_size(new_size, v, &self.block_device);
+    }
+    /// Create a regular file named `name` in this directory.
+    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_typed(name, FileType::RegularFile)
+    }
+    /// Create an inode of `file_type` named `name` in this directory.
+    fn create_typed(&self, name: &str, file_type: FileType) -> Option<Arc<Inode>> {
+        let mut fs = self.fs.lock();
+        let op = |root_inode: &DiskInode| {
+            assert!(root_inode.is_dir());
+            self.find_inode_id(name, root_inode)
+        };
+        if self.read_disk_inode(op).is_some() {
+            return None;
+        }
+        // allocate and initialize a fresh inode
+        let new_inode_id = fs.alloc_inode();
+        let (new_inode_block_id, new_inode_block_offset) = fs.get_disk_inode_pos(new_inode_id);
+        get_block_cache(new_inode_block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
+                new_inode.initialize(file_type);
+            });
+        // append a directory entry to this directory
+        self.modify_disk_inode(|root_inode| {
+            let file_count = (root_inode.size as usize) / DIRENT_SZ;
+            let new_size = (file_count + 1) * DIRENT_SZ;
+            self.increase_size(new_size as u32, root_inode, &mut fs);
+            let dirent = DirEntry::new(name, new_inode_id);
+            root_inode.write_at(
+                file_count * DIRENT_SZ,
+                dirent.as_bytes(),
+                &self.block_device,
+            );
+        });
+        let inode = self.inode_at(new_inode_id, &fs);
+        block_cache_sync_all();
+        Some(inode)
+    }
+    /// Create a symbolic link named `name` whose data holds `target`.
+    pub fn create_symlink(&self, name: &str, target: &str) -> Option<Arc<Inode>> {
+        let inode = self.create_typed(name, FileType::Symlink)?;
+        inode.write_at(0, target.as_bytes());
+        Some(inode)
+    }
+    /// Read the stored target path of a symbolic link.
+    pub fn read_link(&self) -> Vec<u8> {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| {
+            let mut buf = alloc::vec![0u8; disk_inode.size as usize];
+            disk_inode.read_at(0, &mut buf, &self.block_device);
+            buf
+        })
+    }
+    /// List the names of every entry in this directory.
+    pub fn ls(&self) -> Vec<String> {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| {
+            let file_count = (disk_inode.size as usize) / DIRENT_SZ;
+            let mut v: Vec<String> = Vec::new();
+            for i in 0..file_count {
+                let mut dirent = DirEntry::empty();
+                assert_eq!(
+                    disk_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device),
+                    DIRENT_SZ,
+                );
+                v.push(String::from(dirent.name()));
+            }
+            v
+        })
+    }
+    /// Read file data starting at `offset`, updating the access time.
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let fs = self.fs.lock();
+        let size = self.read_disk_inode(|disk_inode| disk_inode.read_at(offset, buf, &self.block_device));
+        if !fs.is_legacy() {
+            let now = super::now();
+            self.modify_disk_inode(|disk_inode| disk_inode.atime = now);
+        }
+        block_cache_sync_all();
+        size
+    }
+    /// Write file data starting at `offset`, growing the file if needed and
+    /// updating the modification and status-change times.
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let mut fs = self.fs.lock();
+        let legacy = fs.is_legacy();
+        let now = super::now();
+        let size = self.modify_disk_inode(|disk_inode| {
+            self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
+            let size = disk_inode.write_at(offset, buf, &self.block_device);
+            if !legacy {
+                disk_inode.mtime = now;
+                disk_inode.ctime = now;
+            }
+            size
+        });
+        block_cache_sync_all();
+        size
+    }
+    /// Truncate the file to zero, freeing every data block and updating the
+    /// modification and status-change times.
+    pub fn clear(&self) {
+        let mut fs = self.fs.lock();
+        let legacy = fs.is_legacy();
+        let now = super::now();
+        self.modify_disk_inode(|disk_inode| {
+            let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
+            for data_block in data_blocks_dealloc.into_iter() {
+                fs.dealloc_data(data_block);
+            }
+            if !legacy {
+                disk_inode.mtime = now;
+                disk_inode.ctime = now;
+            }
+        });
+        block_cache_sync_all();
+    }
+}
@@ -10,7 +10,10 @@ use crate::drivers::BLOCK_DEVICE;
 use crate::mm::UserBuffer;
 use crate::sync::UPSafeCell;
 use alloc::vec::Vec;
-use alloc::{collections::btree_map::BTreeMap, sync::Arc};
+use alloc::{
+    collections::btree_map::BTreeMap,
+    sync::{Arc, Weak},
+};
 use bitflags::*;
 use easy_fs::{EasyFileSystem, Inode};
 use lazy_static::*;
@@ -29,15 +32,89 @@ pub struct OSInodeInner {
     inode: Arc<Inode>,
 }
 
+/// The reference point for an `OSInode::seek`
+pub enum SeekFrom {
+    /// Set the cursor to the given byte offset from the start of the file
+    Start(u64),
+    /// Move the cursor by the given (possibly negative) delta from the current offset
+    Current(i64),
+    /// Move the cursor by the given (possibly negative) delta from the end of the file
+    End(i64),
+}
+
+/// The metadata record returned by the `fstat`/`stat` syscalls.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Stat {
+    /// inode id on the owning filesystem
+    pub ino: u64,
+    /// what kind of object the inode refers to
+    pub ftype: FileType,
+    /// current size in bytes
+    pub size: u64,
+    /// number of hard links
+    pub nlink: u32,
+    /// last access time (nanoseconds since boot)
+    pub atime: u64,
+    /// last modification time (nanoseconds since boot)
+    pub mtime: u64,
+    /// last status-change time (nanoseconds since boot)
+    pub ctime: u64,
+}
+
 impl OSInode {
     /// create a new inode in memory
     pub fn new(readable: bool, writable: bool, inode: Arc<Inode>) -> Self {
+        // Share the canonical `Arc<Inode>` via the cache and register an open
+        // handle so deletion of this file can be deferred until we close.
+        let inode = INODE_CACHE.exclusive_access().open(inode);
         Self {
             readable,
             writable,
             inner: unsafe { UPSafeCell::new(OSInodeInner { offset: 0, inode }) },
         }
     }
+    /// Reposition the stored cursor and return the resulting offset.
+    ///
+    /// Seeking past EOF is allowed: the gap becomes a sparse region that the
+    /// next `write` zero-fills. A seek that would overflow or land before the
+    /// start of the file is rejected with `None` so callers can report
+    /// `EINVAL`, leaving the cursor untouched.
+    pub fn seek(&self, pos: SeekFrom) -> Option<usize> {
+        let mut inner = self.inner.exclusive_access();
+        let new_offset: i64 = match pos {
+            SeekFrom::Start(off) => i64::try_from(off).ok()?,
+            SeekFrom::Current(delta) => (inner.offset as i64).checked_add(delta)?,
+            SeekFrom::End(delta) => (inner.inode.size() as i64).checked_add(delta)?,
+        };
+        if new_offset < 0 {
+            return None;
+        }
+        inner.offset = new_offset as usize;
+        Some(inner.offset)
+    }
+    /// Read at an explicit offset without touching the stored cursor (`pread`).
+    pub fn read_at_pos(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let inner = self.inner.exclusive_access();
+        inner.inode.read_at(offset, buf)
+    }
+    /// Write at an explicit offset without touching the stored cursor (`pwrite`).
+    pub fn write_at_pos(&self, offset: usize, buf: &[u8]) -> usize {
+        let inner = self.inner.exclusive_access();
+        inner.inode.write_at(offset, buf)
+    }
+    /// Owner user id of the underlying inode
+    pub fn uid(&self) -> u32 {
+        self.inner.exclusive_access().inode.uid()
+    }
+    /// Owner group id of the underlying inode
+    pub fn gid(&self) -> u32 {
+        self.inner.exclusive_access().inode.gid()
+    }
+    /// Permission word (rwx triples plus setuid/setgid) of the underlying inode
+    pub fn mode(&self) -> u16 {
+        self.inner.exclusive_access().inode.mode()
+    }
     /// read all data from the inode
     pub fn read_all(&self) -> Vec<u8> {
         let mut inner = self.inner.exclusive_access();
@@ -55,13 +132,140 @@ impl OSInode {
     }
 }
 
+impl Drop for OSInode {
+    fn drop(&mut self) {
+        let inode_id = self.inner.exclusive_access().inode.get_inode_id();
+        INODE_CACHE.exclusive_access().close(inode_id);
+    }
+}
+
 lazy_static! {
     /// The root inode
     pub static ref ROOT_INODE: Arc<Inode> = {
         let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
         Arc::new(EasyFileSystem::root_inode(&efs))
     };
-    pub static ref NLINK_MAP: UPSafeCell<BTreeMap<usize, usize>> = unsafe { UPSafeCell::new(BTreeMap::new()) };
+    /// The canonical in-memory inode table, keyed by inode id.
+    pub static ref INODE_CACHE: UPSafeCell<InodeCache> = unsafe { UPSafeCell::new(InodeCache::new()) };
+}
+
+/// One entry of the [`InodeCache`], tracking the shared state of a single inode.
+struct InodeCacheEntry {
+    /// the one canonical `Arc<Inode>`, held weakly so a closed file can be freed
+    inode: Weak<Inode>,
+    /// hard-link count, formerly tracked by the ad-hoc `NLINK_MAP`
+    nlink: usize,
+    /// number of live open handles (`OSInode`s) referring to this inode
+    handles: usize,
+    /// set when `nlink` reached 0 while handles remained; cleared on last close
+    orphaned: bool,
+}
+
+/// Centralizes link counting and canonical `Arc<Inode>` sharing so that two
+/// opens of the same file observe one inode, and deletion of an open file is
+/// deferred until its last handle closes.
+pub struct InodeCache {
+    entries: BTreeMap<usize, InodeCacheEntry>,
+}
+
+impl InodeCache {
+    fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+    /// Return the canonical `Arc<Inode>` for `found`, registering a new open
+    /// handle. On a cache hit with a live `Weak`, the existing `Arc` is reused
+    /// and `found` is dropped; otherwise `found` becomes the canonical inode.
+    fn open(&mut self, found: Arc<Inode>) -> Arc<Inode> {
+        let inode_id = found.get_inode_id();
+        match self.entries.get_mut(&inode_id) {
+            Some(entry) => {
+                entry.handles += 1;
+                match entry.inode.upgrade() {
+                    Some(existing) => existing,
+                    None => {
+                        entry.inode = Arc::downgrade(&found);
+                        found
+                    }
+                }
+            }
+            None => {
+                self.entries.insert(
+                    inode_id,
+                    InodeCacheEntry {
+                        inode: Arc::downgrade(&found),
+                        nlink: 1,
+                        handles: 1,
+                        orphaned: false,
+                    },
+                );
+                found
+            }
+        }
+    }
+    /// Drop one open handle, performing deferred deletion when an orphaned
+    /// inode loses its last handle.
+    fn close(&mut self, inode_id: usize) {
+        if let Some(entry) = self.entries.get_mut(&inode_id) {
+            entry.handles = entry.handles.saturating_sub(1);
+            if entry.handles == 0 {
+                if entry.orphaned {
+                    if let Some(inode) = entry.inode.upgrade() {
+                        inode.clear();
+                    }
+                    self.entries.remove(&inode_id);
+                } else if entry.nlink == 0 {
+                    self.entries.remove(&inode_id);
+                }
+            }
+        }
+    }
+    fn increase(&mut self, inode: &Arc<Inode>) {
+        let inode_id = inode.get_inode_id();
+        self.entries
+            .entry(inode_id)
+            .and_modify(|e| {
+                e.nlink += 1;
+                // keep a live weak even for an entry first seen via a link
+                if e.inode.upgrade().is_none() {
+                    e.inode = Arc::downgrade(inode);
+                }
+            })
+            .or_insert_with(|| InodeCacheEntry {
+                inode: Arc::downgrade(inode),
+                nlink: 2,
+                handles: 0,
+                orphaned: false,
+            });
+    }
+    fn decrease(&mut self, inode: &Arc<Inode>) {
+        let inode_id = inode.get_inode_id();
+        if let Some(entry) = self.entries.get_mut(&inode_id) {
+            entry.nlink = entry.nlink.saturating_sub(1);
+            if entry.nlink == 0 {
+                if entry.handles == 0 {
+                    // free the blocks through the caller's live handle — the
+                    // cached weak may be dead for an inode that was never
+                    // opened, which previously leaked its blocks.
+                    inode.clear();
+                    self.entries.remove(&inode_id);
+                } else {
+                    // deleted but still open: free the blocks at last close
+                    entry.orphaned = true;
+                }
+            }
+        }
+    }
+    fn nlink(&self, inode_id: usize) -> usize {
+        self.entries.get(&inode_id).map_or(1, |e| e.nlink)
+    }
+}
+
+/// Register the kernel clock with easy-fs so inode timestamps are stamped
+/// with the real time. Called once during filesystem initialization.
+pub fn init() {
+    easy_fs::register_clock(crate::timer::get_time_ns);
 }
 
 /// List all apps in the root directory
@@ -73,6 +277,58 @@ pub fn list_apps() {
     println!("**************/");
 }
 
+bitflags! {
+    /// The read/write/execute permissions requested of, or granted by, an inode
+    pub struct Access: u16 {
+        /// read permission
+        const R = 0b100;
+        /// write permission
+        const W = 0b010;
+        /// execute permission
+        const X = 0b001;
+    }
+}
+
+/// Default permission word for a newly created regular file (`rw-r--r--`)
+pub const DEFAULT_FILE_MODE: u16 = 0o644;
+/// setuid bit within `mode`
+pub const S_ISUID: u16 = 0o4000;
+/// setgid bit within `mode`
+pub const S_ISGID: u16 = 0o2000;
+
+/// Decide whether the given credentials may perform `requested` on a file
+/// owned by (`owner_uid`, `owner_gid`) with permission word `mode`.
+///
+/// The owner triple applies when `uid` matches, otherwise the group triple
+/// when `gid` or one of `supplementary_gids` matches, otherwise the "other"
+/// triple — matching the POSIX rule that the first applicable class is final.
+pub fn check_access(
+    owner_uid: u32,
+    owner_gid: u32,
+    mode: u16,
+    uid: u32,
+    gid: u32,
+    supplementary_gids: &[u32],
+    requested: Access,
+) -> bool {
+    let triple = if uid == owner_uid {
+        (mode >> 6) & 0o7
+    } else if gid == owner_gid || supplementary_gids.contains(&owner_gid) {
+        (mode >> 3) & 0o7
+    } else {
+        mode & 0o7
+    };
+    Access::from_bits_truncate(triple).contains(requested)
+}
+
+// `FileType` is defined alongside the disk inode in easy-fs (so the type
+// stored on disk and the type returned to userspace are one and the same) and
+// re-exported here for the rest of the kernel.
+pub use easy_fs::FileType;
+
+/// Upper bound on symlink hops while resolving a path, guarding against cycles
+const SYMLINK_HOP_LIMIT: usize = 40;
+
 bitflags! {
     ///  The flags argument to the open() system call is constructed by ORing together zero or more of the following values:
     pub struct OpenFlags: u32 {
@@ -86,6 +342,8 @@ bitflags! {
         const CREATE = 1 << 9;
         /// truncate file size to 0
         const TRUNC = 1 << 10;
+        /// open the link itself rather than following it
+        const NOFOLLOW = 1 << 17;
     }
 }
 
@@ -104,55 +362,159 @@ impl OpenFlags {
 }
 
 /// Increase the nlink of inode
-pub fn increase_nlink(inode_id: usize) {
-    if NLINK_MAP.exclusive_access().contains_key(&inode_id) {
-        let mut nlink_map = NLINK_MAP.exclusive_access();
-        let nlink = nlink_map.get_mut(&inode_id).unwrap();
-        *nlink += 1;
-    } else {
-        NLINK_MAP.exclusive_access().insert(inode_id, 2);
-    }
+pub fn increase_nlink(inode: &Arc<Inode>) {
+    INODE_CACHE.exclusive_access().increase(inode);
+    inode.touch_ctime();
 }
 
 /// Decrease the nlink of inode
-pub fn decrease_nlink(inode_id: usize) {
-    let mut nlink_map = NLINK_MAP.exclusive_access();
-    match nlink_map.get_mut(&inode_id) {
-        Some(nlink) => {
-            *nlink -= 1;
-            if *nlink == 0 {
-                nlink_map.remove(&inode_id);
+pub fn decrease_nlink(inode: &Arc<Inode>) {
+    INODE_CACHE.exclusive_access().decrease(inode);
+    inode.touch_ctime();
+}
+
+fn get_nlink(inode_id: usize) -> usize {
+    INODE_CACHE.exclusive_access().nlink(inode_id)
+}
+
+/// Resolve a (possibly multi-component) path to an inode.
+///
+/// The path is split on `/` and each component is looked up with `find`
+/// starting from `ROOT_INODE`. When a component resolves to a `Symlink` its
+/// stored target is read and resolution restarts from it, bounded by
+/// `SYMLINK_HOP_LIMIT` hops to break cycles. If `follow_final` is false the
+/// final component is returned as-is even when it is a symlink, which backs
+/// `OpenFlags::NOFOLLOW`.
+fn resolve_path(path: &str, follow_final: bool) -> Option<Arc<Inode>> {
+    let mut hops = 0usize;
+    let mut cur_path: alloc::string::String = path.into();
+    loop {
+        let components: Vec<&str> = cur_path.split('/').filter(|c| !c.is_empty()).collect();
+        if components.is_empty() {
+            return None;
+        }
+        let mut node = ROOT_INODE.clone();
+        let mut restart: Option<alloc::string::String> = None;
+        for (idx, component) in components.iter().enumerate() {
+            let is_final = idx + 1 == components.len();
+            let child = node.find(component)?;
+            if child.file_type() == FileType::Symlink && !(is_final && !follow_final) {
+                if hops >= SYMLINK_HOP_LIMIT {
+                    return None;
+                }
+                hops += 1;
+                let mut target = alloc::string::String::from_utf8(child.read_link()).ok()?;
+                // a relative symlink target continues from the already-walked prefix
+                if !target.starts_with('/') {
+                    for prefix in components[..idx].iter().rev() {
+                        target = alloc::format!("{}/{}", prefix, target);
+                    }
+                }
+                if !is_final {
+                    for suffix in &components[idx + 1..] {
+                        target = alloc::format!("{}/{}", target, suffix);
+                    }
+                }
+                restart = Some(target);
+                break;
             }
+            node = child;
+        }
+        match restart {
+            Some(next) => cur_path = next,
+            None => return Some(node),
         }
-        None => {}
     }
 }
 
-fn get_nlink(inode_id: usize) -> usize {
-    let nlink_map = NLINK_MAP.exclusive_access();
-    *nlink_map.get(&inode_id).unwrap_or(&1)
+/// Split a path into its parent portion and final component.
+fn split_parent(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    match path.rfind('/') {
+        Some(idx) => (&path[..idx], &path[idx + 1..]),
+        None => ("", path),
+    }
+}
+
+/// Resolve the directory that should contain the final component of `path`,
+/// returning it together with that component name. Intermediate symlinks are
+/// followed; the final component is left untouched so it can be created.
+fn resolve_parent(path: &str) -> Option<(Arc<Inode>, &str)> {
+    let (parent, name) = split_parent(path);
+    if name.is_empty() {
+        return None;
+    }
+    let dir = if parent.is_empty() {
+        ROOT_INODE.clone()
+    } else {
+        resolve_path(parent, true)?
+    };
+    Some((dir, name))
+}
+
+/// Create a symbolic link at `linkpath` whose data holds `target`.
+pub fn symlink(target: &str, linkpath: &str) -> Option<Arc<OSInode>> {
+    let (dir, name) = resolve_parent(linkpath)?;
+    let inode = dir.create_symlink(name, target)?;
+    Some(Arc::new(OSInode::new(false, false, inode)))
 }
 
 /// Open a file
 pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
     let (readable, writable) = flags.read_write();
+    let follow_final = !flags.contains(OpenFlags::NOFOLLOW);
+    let mut requested = Access::empty();
+    requested.set(Access::R, readable);
+    requested.set(Access::W, writable);
+    let (uid, gid, supplementary_gids) = crate::task::current_credentials();
+    // An existing inode may only be opened when its mode permits the requested
+    // access for the calling task's credentials. A `mode` of 0 marks an inode
+    // that carries no permission information: one from an image predating the
+    // permission field, or one created without an explicit mode (the root
+    // directory, a symlink). Access to such inodes is granted rather than bricking the
+    // ability to open (and load) apps from legacy filesystem images.
+    let permitted = |inode: &Arc<Inode>| {
+        let mode = inode.mode();
+        mode == 0
+            || check_access(
+                inode.uid(),
+                inode.gid(),
+                mode,
+                uid,
+                gid,
+                &supplementary_gids,
+                requested,
+            )
+    };
     if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = ROOT_INODE.find(name) {
+        if let Some(inode) = resolve_path(name, follow_final) {
+            if !permitted(&inode) {
+                return None;
+            }
             // clear size
             inode.clear();
             Some(Arc::new(OSInode::new(readable, writable, inode)))
         } else {
-            // create file
-            ROOT_INODE
-                .create(name)
-                .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
+            // create the final component inside its resolved parent directory
+            // rather than as one flat entry literally named `name`
+            let (dir, leaf) = resolve_parent(name)?;
+            dir.create(leaf).map(|inode| {
+                // a freshly created file is owned by its creator with the
+                // conventional default permissions
+                inode.set_owner(uid, gid);
+                inode.set_mode(DEFAULT_FILE_MODE);
+                Arc::new(OSInode::new(readable, writable, inode))
+            })
         }
     } else {
-        ROOT_INODE.find(name).map(|inode| {
+        resolve_path(name, follow_final).and_then(|inode| {
+            if !permitted(&inode) {
+                return None;
+            }
             if flags.contains(OpenFlags::TRUNC) {
                 inode.clear();
             }
-            Arc::new(OSInode::new(readable, writable, inode))
+            Some(Arc::new(OSInode::new(readable, writable, inode)))
         })
     }
 }
@@ -179,6 +541,15 @@ impl File for OSInode {
     }
     fn write(&self, buf: UserBuffer) -> usize {
         let mut inner = self.inner.exclusive_access();
+        // A write by a non-owner clears the setuid/setgid bits, matching
+        // standard filesystem semantics.
+        let (uid, _, _) = crate::task::current_credentials();
+        if uid != inner.inode.uid() {
+            let mode = inner.inode.mode();
+            if mode & (S_ISUID | S_ISGID) != 0 {
+                inner.inode.set_mode(mode & !(S_ISUID | S_ISGID));
+            }
+        }
         let mut total_write_size = 0usize;
         for slice in buf.buffers.iter() {
             let write_size = inner.inode.write_at(inner.offset, *slice);
@@ -196,4 +567,17 @@ impl File for OSInode {
         let inner = self.inner.exclusive_access();
         get_nlink(inner.inode.get_inode_id())
     }
+    fn stat(&self) -> Stat {
+        let inner = self.inner.exclusive_access();
+        let ino = inner.inode.get_inode_id();
+        Stat {
+            ino: ino as u64,
+            ftype: inner.inode.file_type(),
+            size: inner.inode.size() as u64,
+            nlink: get_nlink(ino) as u32,
+            atime: inner.inode.atime(),
+            mtime: inner.inode.mtime(),
+            ctime: inner.inode.ctime(),
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,50 @@
+//! Per-task POSIX credentials (user, group and supplementary groups) and the
+//! helper the filesystem layer uses to consult the caller's identity.
+//!
+//! This module is wired into the task subsystem by `task::mod` with
+//! `mod credentials; pub use credentials::{Credentials, current_credentials};`
+//! and the [`Credentials`] field lives on the task control block.
+
+use super::current_task;
+use alloc::vec::Vec;
+
+/// The identity a task acts under when opening and writing files.
+#[derive(Clone)]
+pub struct Credentials {
+    /// effective user id
+    pub uid: u32,
+    /// effective group id
+    pub gid: u32,
+    /// additional group memberships beyond `gid`
+    pub supplementary_gids: Vec<u32>,
+}
+
+impl Credentials {
+    /// The default identity of the initial task: the root user.
+    pub fn root() -> Self {
+        Self {
+            uid: 0,
+            gid: 0,
+            supplementary_gids: Vec::new(),
+        }
+    }
+}
+
+impl Default for Credentials {
+    fn default() -> Self {
+        Self::root()
+    }
+}
+
+/// Return the calling task's credentials as `(uid, gid, supplementary_gids)`.
+///
+/// The kernel's own bootstrap paths (before any task is running) act as root.
+pub fn current_credentials() -> (u32, u32, Vec<u32>) {
+    match current_task() {
+        Some(task) => {
+            let creds = &task.inner_exclusive_access().credentials;
+            (creds.uid, creds.gid, creds.supplementary_gids.clone())
+        }
+        None => (0, 0, Vec::new()),
+    }
+}